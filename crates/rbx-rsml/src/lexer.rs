@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use logos::Logos;
 use rbx_types::{Color3, Font, Rect, UDim, UDim2, Vector2, Vector3};
 
@@ -73,7 +75,17 @@ pub enum Token<'a> {
     #[regex(r"tw:(slate|gray|zinc|neutral|stone|red|orange|amber|yellow|lime|green|emerald|teal|cyan|sky|blue|indigo|violet|purple|fuchsia|pink|rose)(:(950|900|800|700|600|500|400|300|200|100|50))?", |lex| DataType::ColorTw(lex.slice()), priority = 2)]
     #[regex(r"css:(aliceblue|antiquewhite|aqua|aquamarine|azure|beige|bisque|black|blanchedalmond|blue|blueviolet|brown|burlywood|cadetblue|chartreuse|chocolate|coral|cornflowerblue|cornsilk|crimson|cyan|darkblue|darkcyan|darkgoldenrod|darkgray|darkgreen|darkgrey|darkkhaki|darkmagenta|darkolivegreen|darkorange|darkorchid|darkred|darksalmon|darkseagreen|darkslateblue|darkslategray|darkslategrey|darkturquoise|darkviolet|deeppink|deepskyblue|dimgray|dimgrey|dodgerblue|firebrick|floralwhite|forestgreen|fuchsia|gainsboro|ghostwhite|goldenrod|gold|gray|green|greenyellow|grey|honeydew|hotpink|indianred|indigo|ivory|khaki|lavenderblush|lavender|lawngreen|lemonchiffon|lightblue|lightcoral|lightcyan|lightgoldenrodyellow|lightgray|lightgreen|lightgrey|lightpink|lightsalmon|lightseagreen|lightskyblue|lightslategray|lightslategrey|lightsteelblue|lightyellow|lime|limegreen|linen|magenta|maroon|mediumaquamarine|mediumblue|mediumorchid|mediumpurple|mediumseagreen|mediumslateblue|mediumspringgreen|mediumturquoise|mediumvioletred|midnightblue|mintcream|mistyrose|moccasin|navajowhite|navy|oldlace|olive|olivedrab|orange|orangered|orchid|palegoldenrod|palegreen|paleturquoise|palevioletred|papayawhip|peachpuff|peru|pink|plum|powderblue|purple|rebeccapurple|red|rosybrown|royalblue|saddlebrown|salmon|sandybrown|seagreen|seashell|sienna|silver|skyblue|slateblue|slategray|slategrey|snow|springgreen|steelblue|tan|teal|thistle|tomato|turquoise|violet|wheat|white|whitesmoke|yellow|yellowgreen)", |lex| DataType::ColorCss(lex.slice()), priority = 2)]
     #[regex(r"bc:(white|grey|lightyellow|brickyellow|lightgreen|lightreddishviolet|pastelblue|lightorangebrown|nougat|brightred|medreddishviolet|brightblue|brightyellow|earthorange|black|darkgrey|darkgreen|mediumgreen|ligyellowichorange|brightgreen|darkorange|lightbluishviolet|transparent|trred|trlgblue|trblue|tryellow|lightblue|trflureddishorange|trgreen|trflugreen|phosphwhite|lightred|mediumred|mediumblue|lightgrey|brightviolet|bryellowishorange|brightorange|brightbluishgreen|earthyellow|brightbluishviolet|trbrown|mediumbluishviolet|trmedireddishviolet|medyellowishgreen|medbluishgreen|lightbluishgreen|bryellowishgreen|ligyellowishgreen|medyellowishorange|brreddishorange|brightreddishviolet|lightorange|trbrightbluishviolet|darknougat|silver|neonorange|neongreen|sandblue|sandviolet|mediumorange|sandyellow|earthblue|earthgreen|trflublue|sandbluemetallic|sandvioletmetallic|sandyellowmetallic|darkgreymetallic|blackmetallic|lightgreymetallic|sandgreen|sandred|darkred|trfluyellow|trflured|gunmetallic|redflipflop|yellowflipflop|silverflipflop|curry|fireyellow|flameyellowishorange|reddishbrown|flamereddishorange|mediumstonegrey|royalblue|darkroyalblue|brightreddishlilac|darkstonegrey|lemonmetalic|lightstonegrey|darkcurry|fadedgreen|turquoise|lightroyalblue|mediumroyalblue|brown|reddishlilac|lightlilac|brightpurple|lightpurple|lightpink|lightbrickyellow|warmyellowishorange|coolyellow|doveblue|mediumlilac|slimegreen|smokygrey|darkblue|parsleygreen|steelblue|stormblue|lapis|darkindigo|seagreen|shamrock|fossil|mulberry|forestgreen|cadetblue|electricblue|eggplant|moss|artichoke|sagegreen|ghostgrey|lilac|plum|olivine|laurelgreen|quillgrey|crimson|mint|babyblue|carnationpink|persimmon|maroon|gold|daisyorange|pearl|fog|salmon|terracotta|cocoa|wheat|buttermilk|mauve|sunrise|tawny|rust|cashmere|khaki|lilywhite|seashell|burgundy|cork|burlap|beige|oyster|pinecone|fawnbrown|hurricanegrey|cloudygrey|linen|copper|mediumbrown|bronze|flint|darktaupe|burntsienna|institutionalwhite|midgray|reallyblack|reallyred|deeporange|alder|dustyrose|olive|newyeller|reallyblue|navyblue|deepblue|cyan|cgabrown|magenta|pink|teal|toothpaste|limegreen|camo|grime|lavender|pastellightblue|pastelorange|pastelviolet|pastelbluegreen|pastelgreen|pastelyellow|pastelbrown|royalpurple|hotpink)", |lex| DataType::ColorBc(lex.slice()), priority = 2)]
-    #[regex(r"#[0-9a-fA-F]+", |lex| DataType::ColorHex(lex.slice()))]
+    // `priority = 2` (matching the `ColorTw`/`ColorCss`/`ColorBc` patterns above) makes this win
+    // the tie against `Text(TextType::SelectorName)` below whenever the two match the same span,
+    // so e.g. `#fffff` or `#ff00` lex as a (rejected) `ColorHex` attempt instead of silently
+    // falling back to a brand-new selector name with no diagnostic.
+    #[regex(r"#[0-9a-fA-F]+", |lex| {
+        let hex = str_clip(lex.slice(), 1, 0);
+        match hex.len() {
+            3 | 6 | 8 => Ok(DataType::ColorHex(hex)),
+            _ => Err(()),
+        }
+    }, priority = 2)]
     #[regex(r#"'([^'\n\f\r])*'"#, |lex| DataType::StringSingle(str_clip(lex.slice(), 1, 1)))]
     #[regex(r#""([^"\n\f\r])*""#, |lex| DataType::StringSingle(str_clip(lex.slice(), 1, 1)))]
     #[regex(r"[+-]?([0-9]+([.][0-9]*)?|[.][0-9]+)px", |lex| DataType::NumberOffset(match str_clip(lex.slice(), 0, 2).parse::<f64>() {
@@ -94,6 +106,9 @@ pub enum Token<'a> {
     #[token("false", |_| DataType::Bool(false))]
     DataType(DataType<'a>),
 
+    #[regex(r"rgba?|hsla?", |lex| lex.slice(), priority = 3)]
+    ColorFunction(&'a str),
+
     #[token("+", |_| Operator::Plus)]
     #[token("-", |_| Operator::Sub)]
     #[token("*", |_| Operator::Mult)]
@@ -139,7 +154,10 @@ pub enum Token<'a> {
     PriorityDeclaration,
 
     #[token("@derive")]
-    DeriveDeclaration
+    DeriveDeclaration,
+
+    #[regex(r"@[a-zA-Z0-9_-]+", |lex| str_clip(lex.slice(), 1, 0), priority = 1)]
+    MacroInvocation(&'a str),
 }
 
 pub type RsmlLexer<'a> = logos::Lexer<'a, Token<'a>>;
@@ -156,3 +174,185 @@ pub fn lex_rsml(source: &str) -> Vec<Token<'_>> {
         None
     }).collect()
 }
+
+/// A semantic class for a span of source text, intended for editor/LSP consumers.
+///
+/// The variant names double as the tag strings returned from [`HighlightTag::as_str`], using
+/// the same dotted naming convention editors commonly expect (e.g. TextMate scopes).
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
+pub enum HighlightTag {
+    SelectorName,
+    SelectorTag,
+    SelectorState,
+    SelectorPseudo,
+    Variable,
+    Argument,
+    PseudoProperty,
+    Color,
+    Number,
+    String,
+    ConstantBuiltin,
+    Keyword,
+    Operator,
+    Comment,
+}
+
+impl HighlightTag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HighlightTag::SelectorName => "selector.name",
+            HighlightTag::SelectorTag => "selector.tag",
+            HighlightTag::SelectorState => "selector.state",
+            HighlightTag::SelectorPseudo => "selector.pseudo",
+            HighlightTag::Variable => "variable",
+            HighlightTag::Argument => "argument",
+            HighlightTag::PseudoProperty => "property.pseudo",
+            HighlightTag::Color => "color",
+            HighlightTag::Number => "number",
+            HighlightTag::String => "string",
+            HighlightTag::ConstantBuiltin => "constant.builtin",
+            HighlightTag::Keyword => "keyword",
+            HighlightTag::Operator => "operator",
+            HighlightTag::Comment => "comment",
+        }
+    }
+}
+
+/// A byte-range tagged with the [`HighlightTag`] it should be colorized as.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HighlightSpan {
+    pub range: Range<usize>,
+    pub tag: HighlightTag,
+}
+
+fn text_type_tag(text_type: &TextType) -> Option<HighlightTag> {
+    match text_type {
+        TextType::NonSpecial(_) => None,
+        TextType::SelectorName(_) => Some(HighlightTag::SelectorName),
+        TextType::SelectorTagOrEnumPart(_) => Some(HighlightTag::SelectorTag),
+        TextType::SelectorStateOrEnumPart(_) => Some(HighlightTag::SelectorState),
+        TextType::SelectorPsuedo(_) => Some(HighlightTag::SelectorPseudo),
+        TextType::Argument(_) => Some(HighlightTag::Argument),
+        TextType::Variable(_) => Some(HighlightTag::Variable),
+        TextType::PsuedoProperty(_) => Some(HighlightTag::PseudoProperty),
+    }
+}
+
+fn data_type_tag(data_type: &DataType) -> HighlightTag {
+    match data_type {
+        DataType::ColorHex(_) | DataType::ColorTw(_) | DataType::ColorCss(_) | DataType::ColorBc(_) => HighlightTag::Color,
+        DataType::NumberOffset(_) | DataType::NumberScale(_) | DataType::Number(_) => HighlightTag::Number,
+        DataType::StringSingle(_) => HighlightTag::String,
+        DataType::Bool(_) => HighlightTag::ConstantBuiltin,
+
+        // The remaining variants are only ever produced by later evaluation passes, not the lexer.
+        DataType::Tuple(_) | DataType::UDim(_) | DataType::UDim2(_) | DataType::Vec2(_)
+        | DataType::Rect(_) | DataType::Vec3(_) | DataType::Color3(_) | DataType::Font(_)
+        | DataType::OwnedString(_) => HighlightTag::Number,
+    }
+}
+
+/// Lexes `source` and returns a flat list of byte-range/tag pairs suitable for semantic
+/// highlighting, without requiring callers to re-implement the grammar.
+///
+/// A `--[[ ... ]]` multi-line comment is reported as a single [`HighlightTag::Comment`] span
+/// running from the opening `--[[` to the matching `]]` (or to the end of the source if it is
+/// never closed), rather than as highlights for whatever tokens happen to lex inside it.
+pub fn highlight_rsml(source: &str) -> Vec<HighlightSpan> {
+    let mut spans = Vec::new();
+    let mut lexer = Token::lexer(source).spanned();
+
+    while let Some((token, span)) = lexer.next() {
+        let Ok(token) = token else { continue };
+
+        match token {
+            Token::CommentMultiStart => {
+                let mut end = source.len();
+                for (inner_token, inner_span) in lexer.by_ref() {
+                    if inner_token == Ok(Token::CommentMultiEnd) {
+                        end = inner_span.end;
+                        break;
+                    }
+                }
+                spans.push(HighlightSpan { range: span.start..end, tag: HighlightTag::Comment });
+            }
+            Token::CommentSingle => {
+                spans.push(HighlightSpan { range: span, tag: HighlightTag::Comment });
+            }
+            Token::Text(text_type) => {
+                if let Some(tag) = text_type_tag(&text_type) {
+                    spans.push(HighlightSpan { range: span, tag });
+                }
+            }
+            Token::DataType(data_type) => {
+                spans.push(HighlightSpan { range: span, tag: data_type_tag(&data_type) });
+            }
+            Token::ColorFunction(_) => {
+                spans.push(HighlightSpan { range: span, tag: HighlightTag::Color });
+            }
+            Token::Operator(_) => {
+                spans.push(HighlightSpan { range: span, tag: HighlightTag::Operator });
+            }
+            Token::EnumKeyword
+            | Token::MacroDeclaration
+            | Token::PriorityDeclaration
+            | Token::DeriveDeclaration
+            | Token::MacroInvocation(_) => {
+                spans.push(HighlightSpan { range: span, tag: HighlightTag::Keyword });
+            }
+            Token::ScopeOpen | Token::ScopeClose | Token::SectionClose | Token::ListDelimiter
+            | Token::Equals | Token::Colon | Token::ScopeToChildren | Token::ScopeToDescendants
+            | Token::TupleOpen | Token::TupleClose | Token::CommentMultiEnd => {}
+        }
+    }
+
+    spans
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tags_selector_variable_and_number() {
+        let spans = highlight_rsml("#button { Size = 42; $brand = 1; }");
+
+        assert_eq!(spans[0], HighlightSpan { range: 0..7, tag: HighlightTag::SelectorName });
+        assert!(spans.iter().any(|span| span.tag == HighlightTag::Number));
+        assert!(spans.iter().any(|span| span.tag == HighlightTag::Variable));
+    }
+
+    #[test]
+    fn tags_operator_and_keyword() {
+        let spans = highlight_rsml("@macro foo(a) { Size = 1 + 2; }");
+
+        assert_eq!(spans[0], HighlightSpan { range: 0..6, tag: HighlightTag::Keyword });
+        assert!(spans.iter().any(|span| span.tag == HighlightTag::Operator));
+    }
+
+    #[test]
+    fn terminated_multiline_comment_spans_start_to_end_marker() {
+        let source = "--[[ hidden ]] Size";
+        let spans = highlight_rsml(source);
+        let comment_end = source.find("]]").unwrap() + 2;
+
+        assert_eq!(spans[0], HighlightSpan { range: 0..comment_end, tag: HighlightTag::Comment });
+    }
+
+    #[test]
+    fn unterminated_multiline_comment_spans_to_end_of_source() {
+        let source = "--[[ hidden forever";
+        let spans = highlight_rsml(source);
+
+        assert_eq!(spans, vec![HighlightSpan { range: 0..source.len(), tag: HighlightTag::Comment }]);
+    }
+
+    #[test]
+    fn single_line_comment_is_tagged_without_consuming_the_newline() {
+        let source = "-- a comment\n42";
+        let spans = highlight_rsml(source);
+
+        assert_eq!(spans[0], HighlightSpan { range: 0..12, tag: HighlightTag::Comment });
+        assert_eq!(spans[1], HighlightSpan { range: 13..15, tag: HighlightTag::Number });
+    }
+}
@@ -0,0 +1,12 @@
+mod lexer;
+mod parser;
+
+pub use lexer::{
+    highlight_rsml, lex_rsml, DataType, HighlightSpan, HighlightTag, Operator, RsmlLexer, Token,
+    TextType,
+};
+pub use parser::{
+    data_type_to_variant, lex_rsml_diagnostics, parse_rsml, parse_rsml_diagnostics,
+    parse_rsml_diagnostics_with_inherited, Arena, LexDiagnostic, MacroDef, RuleMap, SpannedToken,
+    TokenTreeNode,
+};
@@ -0,0 +1,1028 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use logos::Logos;
+use rbx_dom_weak::types::Variant;
+use rbx_types::{Color3, UDim, Vector2, Vector3};
+
+use crate::lexer::{DataType, Operator, TextType, Token};
+
+/// A single byte-range-tagged problem found while lexing or parsing a `.rsml` source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexDiagnostic {
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl LexDiagnostic {
+    fn new(span: Range<usize>, message: impl Into<String>) -> Self {
+        LexDiagnostic { span, message: message.into() }
+    }
+}
+
+/// A token paired with the byte range it was lexed from.
+pub type SpannedToken<'a> = (Token<'a>, Range<usize>);
+
+/// Lexes `source`, returning every token that parsed successfully alongside a diagnostic for
+/// every slice that didn't, instead of silently dropping the bad ones like [`crate::lex_rsml`].
+pub fn lex_rsml_diagnostics(source: &str) -> (Vec<SpannedToken<'_>>, Vec<LexDiagnostic>) {
+    let mut tokens = Vec::new();
+    let mut diagnostics = Vec::new();
+
+    for (token, span) in Token::lexer(source).spanned() {
+        match token {
+            Ok(token) => tokens.push((token, span)),
+            Err(_) => diagnostics.push(LexDiagnostic::new(
+                span.clone(),
+                format!("unrecognized token {:?}", &source[span]),
+            )),
+        }
+    }
+
+    (tokens, diagnostics)
+}
+
+/// A parsed `@macro` definition: its declared parameter names and the captured token slice of
+/// its body, re-parsed with arguments substituted in at each invocation site.
+#[derive(Debug, Clone)]
+pub struct MacroDef<'a> {
+    pub params: Vec<&'a str>,
+    pub body: Vec<SpannedToken<'a>>,
+}
+
+/// A simple append-only arena: nodes are referenced by the index they were allocated at. Also
+/// carries the `@macro` definitions collected while parsing, keyed by name.
+#[derive(Debug, Default)]
+pub struct Arena<'a, T> {
+    nodes: Vec<T>,
+    pub macros: HashMap<&'a str, MacroDef<'a>>,
+}
+
+impl<'a, T> Arena<'a, T> {
+    pub fn new() -> Self {
+        Arena { nodes: Vec::new(), macros: HashMap::new() }
+    }
+
+    pub fn alloc(&mut self, node: T) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.nodes.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.nodes.get_mut(index)
+    }
+}
+
+/// Child rules of a node, keyed by selector text. Each selector's rule indices are kept in the
+/// order they were declared; the selectors themselves are not kept in any particular order.
+#[derive(Debug, Default)]
+pub struct RuleMap(pub HashMap<String, Vec<usize>>);
+
+impl RuleMap {
+    fn push(&mut self, selector: String, child_idx: usize) {
+        self.0.entry(selector).or_default().push(child_idx);
+    }
+}
+
+/// A single node of the parsed token tree: either the root stylesheet or one `StyleRule` body.
+#[derive(Debug, Default)]
+pub struct TokenTreeNode<'a> {
+    pub rules: RuleMap,
+    pub variables: HashMap<&'a str, DataType<'a>>,
+    pub properties: HashMap<&'a str, DataType<'a>>,
+    pub priority: Option<i32>,
+    /// Decoded from an 8-digit hex color or an `rgba()`/`hsla()` alpha channel, since
+    /// Roblox's `Color3` carries no alpha of its own.
+    pub transparency: Option<f64>,
+}
+
+/// Converts a folded [`DataType`] into the `Variant` a snapshot stores on the instance.
+pub fn data_type_to_variant(data_type: &DataType) -> Variant {
+    match data_type {
+        DataType::Number(n) => Variant::Float64(*n),
+        DataType::NumberOffset(n) => Variant::Float64(*n),
+        DataType::NumberScale(n) => Variant::Float64(*n),
+        DataType::Bool(b) => Variant::Bool(*b),
+        DataType::StringSingle(s) => Variant::String((*s).to_string()),
+        DataType::OwnedString(s) => Variant::String(s.clone()),
+        DataType::ColorHex(s) | DataType::ColorTw(s) | DataType::ColorCss(s) | DataType::ColorBc(s) => {
+            Variant::String((*s).to_string())
+        }
+        DataType::UDim(value) => Variant::UDim(*value),
+        DataType::UDim2(value) => Variant::UDim2(*value),
+        DataType::Vec2(value) => Variant::Vector2(*value),
+        DataType::Vec3(value) => Variant::Vector3(*value),
+        DataType::Rect(value) => Variant::Rect(*value),
+        DataType::Color3(value) => Variant::Color3(*value),
+        DataType::Font(value) => Variant::Font(value.clone()),
+        DataType::Tuple(_) => Variant::String(String::new()),
+    }
+}
+
+/// Parses a `3`/`6`/`8`-digit hex literal (without the leading `#`) into an RGB color and,
+/// for the 8-digit form, a `Transparency` derived from the alpha channel.
+fn parse_hex_color(hex: &str) -> Option<(Color3, Option<f64>)> {
+    let expand_nibble = |nibble: u32| -> f32 { ((nibble << 4) | nibble) as f32 / 255.0 };
+    let channel = |byte: u32| -> f32 { byte as f32 / 255.0 };
+
+    match hex.len() {
+        3 => {
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            let r = expand_nibble((value >> 8) & 0xF);
+            let g = expand_nibble((value >> 4) & 0xF);
+            let b = expand_nibble(value & 0xF);
+            Some((Color3::new(r, g, b), None))
+        }
+        6 => {
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            let r = channel((value >> 16) & 0xFF);
+            let g = channel((value >> 8) & 0xFF);
+            let b = channel(value & 0xFF);
+            Some((Color3::new(r, g, b), None))
+        }
+        8 => {
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            let r = channel((value >> 24) & 0xFF);
+            let g = channel((value >> 16) & 0xFF);
+            let b = channel((value >> 8) & 0xFF);
+            let a = (value & 0xFF) as f64 / 255.0;
+            Some((Color3::new(r, g, b), Some(1.0 - a)))
+        }
+        _ => None,
+    }
+}
+
+/// Standard piecewise HSL→RGB conversion, `h` in degrees and `s`/`l` as `0.0..=1.0` fractions.
+fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (f32, f32, f32) {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - (((h / 60.0) % 2.0) - 1.0).abs());
+    let m = l - c / 2.0;
+
+    let (r, g, b) = match h.rem_euclid(360.0) {
+        h if h < 60.0 => (c, x, 0.0),
+        h if h < 120.0 => (x, c, 0.0),
+        h if h < 180.0 => (0.0, c, x),
+        h if h < 240.0 => (0.0, x, c),
+        h if h < 300.0 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    ((r + m) as f32, (g + m) as f32, (b + m) as f32)
+}
+
+fn precedence(op: &Operator) -> u8 {
+    match op {
+        Operator::Pow => 3,
+        Operator::Mult | Operator::Div | Operator::Mod => 2,
+        Operator::Plus | Operator::Sub => 1,
+    }
+}
+
+fn number_op(op: &Operator, a: f64, b: f64) -> f64 {
+    match op {
+        Operator::Plus => a + b,
+        Operator::Sub => a - b,
+        Operator::Mult => a * b,
+        Operator::Div => a / b,
+        Operator::Pow => a.powf(b),
+        Operator::Mod => a % b,
+    }
+}
+
+/// Applies the type-aware promotion rules for arithmetic between two folded [`DataType`]s:
+/// `NumberOffset +/- NumberOffset` and `NumberScale +/- NumberScale` fold within their own unit,
+/// `NumberScale +/- NumberOffset` becomes a [`UDim`], `UDim`/`Vec2`/`Vec3` combine component-wise
+/// with their own kind or scale against a plain `Number`, and plain numbers fold to a number.
+fn apply_operator<'a>(op: &Operator, lhs: DataType<'a>, rhs: DataType<'a>) -> Result<DataType<'a>, String> {
+    match (lhs, rhs) {
+        (DataType::Number(a), DataType::Number(b)) => Ok(DataType::Number(number_op(op, a, b))),
+
+        (DataType::NumberOffset(a), DataType::NumberOffset(b)) if matches!(op, Operator::Plus | Operator::Sub) => {
+            Ok(DataType::NumberOffset(number_op(op, a, b)))
+        }
+
+        (DataType::NumberScale(a), DataType::NumberScale(b)) if matches!(op, Operator::Plus | Operator::Sub) => {
+            Ok(DataType::NumberScale(number_op(op, a, b)))
+        }
+
+        (DataType::NumberScale(scale), DataType::NumberOffset(offset))
+        | (DataType::NumberOffset(offset), DataType::NumberScale(scale))
+            if matches!(op, Operator::Plus | Operator::Sub) =>
+        {
+            let offset = if matches!(op, Operator::Sub) { -offset } else { offset };
+            Ok(DataType::UDim(UDim::new(scale as f32, offset as i32)))
+        }
+
+        (DataType::UDim(a), DataType::UDim(b)) if matches!(op, Operator::Plus | Operator::Sub) => {
+            Ok(DataType::UDim(UDim::new(
+                number_op(op, a.scale as f64, b.scale as f64) as f32,
+                number_op(op, a.offset as f64, b.offset as f64) as i32,
+            )))
+        }
+
+        (DataType::Number(n), DataType::UDim(u)) | (DataType::UDim(u), DataType::Number(n))
+            if matches!(op, Operator::Mult) =>
+        {
+            Ok(DataType::UDim(UDim::new((u.scale as f64 * n) as f32, (u.offset as f64 * n) as i32)))
+        }
+
+        (DataType::Vec2(a), DataType::Vec2(b)) if matches!(op, Operator::Plus | Operator::Sub) => {
+            Ok(DataType::Vec2(Vector2::new(
+                number_op(op, a.x as f64, b.x as f64) as f32,
+                number_op(op, a.y as f64, b.y as f64) as f32,
+            )))
+        }
+        (DataType::Number(n), DataType::Vec2(v)) | (DataType::Vec2(v), DataType::Number(n))
+            if matches!(op, Operator::Mult) =>
+        {
+            Ok(DataType::Vec2(Vector2::new((v.x as f64 * n) as f32, (v.y as f64 * n) as f32)))
+        }
+
+        (DataType::Vec3(a), DataType::Vec3(b)) if matches!(op, Operator::Plus | Operator::Sub) => {
+            Ok(DataType::Vec3(Vector3::new(
+                number_op(op, a.x as f64, b.x as f64) as f32,
+                number_op(op, a.y as f64, b.y as f64) as f32,
+                number_op(op, a.z as f64, b.z as f64) as f32,
+            )))
+        }
+        (DataType::Number(n), DataType::Vec3(v)) | (DataType::Vec3(v), DataType::Number(n))
+            if matches!(op, Operator::Mult) =>
+        {
+            Ok(DataType::Vec3(Vector3::new(
+                (v.x as f64 * n) as f32,
+                (v.y as f64 * n) as f32,
+                (v.z as f64 * n) as f32,
+            )))
+        }
+
+        (lhs, rhs) => Err(format!("cannot apply {op:?} to {lhs:?} and {rhs:?}")),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ExprToken<'a> {
+    Value(DataType<'a>),
+    Op(Operator),
+}
+
+/// An entry on the shunting-yard operator stack: either a pending [`Operator`] or a marker for
+/// an open `(` waiting for its matching `)`.
+enum StackEntry {
+    Op(Operator),
+    LParen,
+}
+
+/// Evaluates an already shunting-yarded RPN expression with a value stack of [`DataType`]s.
+fn evaluate_rpn<'a>(rpn: &[ExprToken<'a>]) -> Result<DataType<'a>, String> {
+    let mut stack: Vec<DataType<'a>> = Vec::new();
+
+    for item in rpn {
+        match item {
+            ExprToken::Value(value) => stack.push(value.clone()),
+            ExprToken::Op(op) => {
+                let rhs = stack.pop().ok_or("expression is missing an operand")?;
+                let lhs = stack.pop().ok_or("expression is missing an operand")?;
+                stack.push(apply_operator(op, lhs, rhs)?);
+            }
+        }
+    }
+
+    match stack.len() {
+        1 => Ok(stack.pop().unwrap()),
+        0 => Err("expected a value".to_string()),
+        _ => Err("expression has trailing values".to_string()),
+    }
+}
+
+/// Substitutes every `$!param` ([`TextType::Argument`]) in a macro's captured body with the
+/// corresponding argument's token slice, leaving ordinary `$variable` references untouched so
+/// they resolve against the invocation site's enclosing scope instead.
+fn substitute_arguments<'a>(macro_def: &MacroDef<'a>, args: &[Vec<SpannedToken<'a>>]) -> Vec<SpannedToken<'a>> {
+    let mut output = Vec::new();
+
+    for (token, span) in &macro_def.body {
+        if let Token::Text(TextType::Argument(arg_name)) = token {
+            if let Some(index) = macro_def.params.iter().position(|param| param == arg_name) {
+                output.extend(args[index].iter().cloned());
+                continue;
+            }
+        }
+        output.push((token.clone(), span.clone()));
+    }
+
+    output
+}
+
+fn selector_fragment(text_type: &TextType<'_>) -> String {
+    match text_type {
+        TextType::NonSpecial(text) => (*text).to_string(),
+        TextType::SelectorName(text) => format!("#{text}"),
+        TextType::SelectorTagOrEnumPart(text) => format!(".{text}"),
+        TextType::SelectorStateOrEnumPart(text) => format!(":{text}"),
+        TextType::SelectorPsuedo(text) => format!("::{text}"),
+        TextType::Argument(text) => format!("$!{text}"),
+        TextType::Variable(text) => format!("${text}"),
+        TextType::PsuedoProperty(text) => format!("!{text}"),
+    }
+}
+
+struct Parser<'a, 'b> {
+    tokens: &'b [SpannedToken<'a>],
+    pos: usize,
+    diagnostics: Vec<LexDiagnostic>,
+    /// `$variable` values inherited from a `@derive` ancestor, consulted when a name isn't
+    /// defined in the current node. Owned rather than borrowed from `'a` since ancestors are
+    /// parsed from a different file's (already-dropped) source text.
+    inherited_variables: &'b HashMap<String, DataType<'static>>,
+    /// How many rule bodies deep the parser currently is; `0` is the root. Used to reject
+    /// `@derive` below the root, since `scan_derive_paths` only ever honors root-level ones.
+    depth: usize,
+}
+
+impl<'a, 'b> Parser<'a, 'b> {
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn span_at(&self, index: usize) -> Range<usize> {
+        self.tokens.get(index).map(|(_, span)| span.clone())
+            .or_else(|| self.tokens.last().map(|(_, span)| span.clone()))
+            .unwrap_or(0..0)
+    }
+
+    fn bump(&mut self) -> Option<&SpannedToken<'a>> {
+        let token = self.tokens.get(self.pos);
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// Skips tokens until (and including) the next `;` or `}` so a malformed statement doesn't
+    /// take the rest of the file down with it.
+    fn resync(&mut self) {
+        while let Some((token, _)) = self.tokens.get(self.pos) {
+            self.pos += 1;
+            if matches!(token, Token::SectionClose | Token::ScopeClose) {
+                break;
+            }
+        }
+    }
+
+    fn error(&mut self, span: Range<usize>, message: impl Into<String>) {
+        self.diagnostics.push(LexDiagnostic::new(span, message.into()));
+    }
+
+    /// Parses the body of a node (root or rule) up to a closing `}` or end of input.
+    fn parse_body(
+        &mut self,
+        arena: &mut Arena<'a, TokenTreeNode<'a>>,
+        macro_stack: &mut Vec<&'a str>,
+    ) -> TokenTreeNode<'a> {
+        let mut node = TokenTreeNode::default();
+        self.parse_statements_into(&mut node, arena, macro_stack);
+        node
+    }
+
+    /// Parses statements (declarations, variables, properties, rules, macro invocations) up to
+    /// a closing `}` or end of input, splicing them into an already-existing `node` rather than
+    /// allocating a fresh one. Shared by [`Self::parse_body`] and macro expansion, which splices
+    /// a macro's expanded body into the invocation site's node as if written inline.
+    fn parse_statements_into(
+        &mut self,
+        node: &mut TokenTreeNode<'a>,
+        arena: &mut Arena<'a, TokenTreeNode<'a>>,
+        macro_stack: &mut Vec<&'a str>,
+    ) {
+        loop {
+            match self.peek() {
+                None | Some(Token::ScopeClose) => {
+                    self.bump();
+                    break;
+                }
+                Some(Token::PriorityDeclaration) => {
+                    self.bump();
+                    match self.peek() {
+                        Some(Token::DataType(DataType::Number(n))) => {
+                            node.priority = Some(*n as i32);
+                            self.bump();
+                        }
+                        _ => {
+                            let span = self.span_at(self.pos);
+                            self.error(span, "expected a number after @priority");
+                        }
+                    }
+                    if !matches!(self.peek(), Some(Token::SectionClose)) {
+                        let span = self.span_at(self.pos);
+                        self.error(span, "expected ';' after @priority declaration");
+                        self.resync();
+                    } else {
+                        self.bump();
+                    }
+                }
+                Some(Token::DeriveDeclaration) => {
+                    // The path itself is read by `scan_derive_paths`'s raw pre-scan, which runs
+                    // before ancestor variables are resolved; we only validate the syntax here.
+                    let span = self.span_at(self.pos);
+                    self.bump();
+                    if self.depth > 0 {
+                        self.error(span, "@derive is only allowed at the top level of a file");
+                    }
+                    match self.peek() {
+                        Some(Token::DataType(DataType::StringSingle(_))) => {
+                            self.bump();
+                        }
+                        _ => {
+                            let span = self.span_at(self.pos);
+                            self.error(span, "expected a string path after @derive");
+                        }
+                    }
+                    if !matches!(self.peek(), Some(Token::SectionClose)) {
+                        let span = self.span_at(self.pos);
+                        self.error(span, "expected ';' after @derive declaration");
+                        self.resync();
+                    } else {
+                        self.bump();
+                    }
+                }
+                Some(Token::MacroDeclaration) => {
+                    self.bump();
+                    self.parse_macro_declaration(arena);
+                }
+                Some(Token::MacroInvocation(name)) => {
+                    let name = *name;
+                    let span = self.span_at(self.pos);
+                    self.bump();
+                    self.expand_invocation(name, span, node, arena, macro_stack);
+                }
+                Some(Token::Text(TextType::Variable(name))) if self.assignment_follows() => {
+                    let name = *name;
+                    self.bump();
+                    self.bump(); // '='
+                    match self.read_expr(node) {
+                        Some((value, _transparency)) => { node.variables.insert(name, value); }
+                        None => {
+                            let span = self.span_at(self.pos);
+                            self.error(span, format!("expected a value for variable '${name}'"));
+                        }
+                    }
+                    self.expect_section_close();
+                }
+                Some(Token::Text(TextType::PsuedoProperty(name))) if self.assignment_follows() => {
+                    let name = *name;
+                    self.bump();
+                    self.bump(); // '='
+                    self.parse_property(name, node);
+                }
+                Some(Token::Text(TextType::NonSpecial(name))) if self.assignment_follows() => {
+                    let name = *name;
+                    self.bump();
+                    self.bump(); // '='
+                    self.parse_property(name, node);
+                }
+                Some(_) => {
+                    match self.parse_selector() {
+                        Some(selector) => {
+                            if !matches!(self.peek(), Some(Token::ScopeOpen)) {
+                                let span = self.span_at(self.pos);
+                                self.error(span, format!("expected '{{' to open the body of '{selector}'"));
+                                self.resync();
+                                continue;
+                            }
+                            self.bump(); // '{'
+                            self.depth += 1;
+                            let child = self.parse_body(arena, macro_stack);
+                            self.depth -= 1;
+                            let child_idx = arena.alloc(child);
+                            node.rules.push(selector, child_idx);
+                        }
+                        None => {
+                            let span = self.span_at(self.pos);
+                            self.error(span, "unexpected token");
+                            self.resync();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn assignment_follows(&self) -> bool {
+        matches!(self.tokens.get(self.pos + 1), Some((Token::Equals, _)))
+    }
+
+    fn parse_property(&mut self, name: &'a str, node: &mut TokenTreeNode<'a>) {
+        match self.read_expr(node) {
+            Some((value, transparency)) => {
+                node.properties.insert(name, value);
+                if let Some(transparency) = transparency {
+                    node.transparency = Some(transparency);
+                }
+            }
+            None => {
+                let span = self.span_at(self.pos);
+                self.error(span, format!("expected a value for property '{name}'"));
+            }
+        }
+        self.expect_section_close();
+    }
+
+    fn expect_section_close(&mut self) {
+        if matches!(self.peek(), Some(Token::SectionClose)) {
+            self.bump();
+        } else {
+            let span = self.span_at(self.pos);
+            self.error(span, "expected ';' to close the statement");
+            self.resync();
+        }
+    }
+
+    /// Parses `@macro name(param, ...) { ...body... }`, assuming `@macro` has already been
+    /// consumed, and stashes the parameter list and captured body token slice on `arena` keyed
+    /// by name. The body is not parsed here; it is re-parsed, with arguments substituted in,
+    /// once at each invocation site by [`Self::expand_invocation`].
+    fn parse_macro_declaration(&mut self, arena: &mut Arena<'a, TokenTreeNode<'a>>) {
+        let name = match self.peek().cloned() {
+            Some(Token::Text(TextType::NonSpecial(name))) => {
+                self.bump();
+                name
+            }
+            _ => {
+                let span = self.span_at(self.pos);
+                self.error(span, "expected a name after @macro");
+                self.resync();
+                return;
+            }
+        };
+
+        if !matches!(self.peek(), Some(Token::TupleOpen)) {
+            let span = self.span_at(self.pos);
+            self.error(span, format!("expected '(' after macro name '{name}'"));
+            self.resync();
+            return;
+        }
+        self.bump();
+
+        let mut params = Vec::new();
+        while let Some(Token::Text(TextType::NonSpecial(param))) = self.peek().cloned() {
+            params.push(param);
+            self.bump();
+            if matches!(self.peek(), Some(Token::ListDelimiter)) {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+
+        if !matches!(self.peek(), Some(Token::TupleClose)) {
+            let span = self.span_at(self.pos);
+            self.error(span, format!("expected ')' after macro '{name}' parameters"));
+            self.resync();
+            return;
+        }
+        self.bump();
+
+        if !matches!(self.peek(), Some(Token::ScopeOpen)) {
+            let span = self.span_at(self.pos);
+            self.error(span, format!("expected '{{' to open the body of macro '{name}'"));
+            self.resync();
+            return;
+        }
+        self.bump();
+
+        let body_start = self.pos;
+        let mut depth = 1;
+        let body_end = loop {
+            match self.peek() {
+                Some(Token::ScopeOpen) => {
+                    depth += 1;
+                    self.bump();
+                }
+                Some(Token::ScopeClose) => {
+                    depth -= 1;
+                    let end = self.pos;
+                    self.bump();
+                    if depth == 0 {
+                        break end;
+                    }
+                }
+                None => {
+                    let span = self.span_at(self.pos);
+                    self.error(span, format!("unterminated body for macro '{name}'"));
+                    break self.pos;
+                }
+                _ => {
+                    self.bump();
+                }
+            }
+        };
+        let body = self.tokens[body_start..body_end].to_vec();
+
+        arena.macros.insert(name, MacroDef { params, body });
+    }
+
+    /// Expands a `@name(arg, ...)` invocation: parses the argument list and trailing `;`, then
+    /// substitutes the macro's parameters with the given argument token slices and splices the
+    /// resulting statements directly into `node`, as if the macro's body had been written inline.
+    /// Guards against unknown macros, arity mismatches, and macro re-entry via `macro_stack`.
+    fn expand_invocation(
+        &mut self,
+        name: &'a str,
+        span: Range<usize>,
+        node: &mut TokenTreeNode<'a>,
+        arena: &mut Arena<'a, TokenTreeNode<'a>>,
+        macro_stack: &mut Vec<&'a str>,
+    ) {
+        if !matches!(self.peek(), Some(Token::TupleOpen)) {
+            self.error(span, format!("expected '(' after macro invocation '@{name}'"));
+            self.resync();
+            return;
+        }
+        self.bump();
+
+        let mut args: Vec<Vec<SpannedToken<'a>>> = Vec::new();
+        if !matches!(self.peek(), Some(Token::TupleClose)) {
+            loop {
+                let mut arg = Vec::new();
+                let mut depth = 0i32;
+                loop {
+                    match self.peek() {
+                        Some(Token::TupleClose) if depth == 0 => break,
+                        Some(Token::ListDelimiter) if depth == 0 => break,
+                        Some(Token::TupleOpen) => depth += 1,
+                        Some(Token::TupleClose) => depth -= 1,
+                        None => break,
+                        _ => {}
+                    }
+                    match self.bump() {
+                        Some((token, token_span)) => arg.push((token.clone(), token_span.clone())),
+                        None => break,
+                    }
+                }
+                args.push(arg);
+
+                if matches!(self.peek(), Some(Token::ListDelimiter)) {
+                    self.bump();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        if !matches!(self.peek(), Some(Token::TupleClose)) {
+            let close_span = self.span_at(self.pos);
+            self.error(close_span, format!("expected ')' to close macro invocation '@{name}'"));
+            self.resync();
+            return;
+        }
+        self.bump();
+        self.expect_section_close();
+
+        let Some(macro_def) = arena.macros.get(name).cloned() else {
+            self.error(span, format!("unknown macro '@{name}'"));
+            return;
+        };
+        if macro_def.params.len() != args.len() {
+            self.error(span, format!(
+                "macro '@{name}' expects {} argument(s), got {}",
+                macro_def.params.len(),
+                args.len(),
+            ));
+            return;
+        }
+        if macro_stack.contains(&name) {
+            self.error(span, format!("macro '@{name}' recurses into itself"));
+            return;
+        }
+
+        let substituted = substitute_arguments(&macro_def, &args);
+        macro_stack.push(name);
+        let mut sub_parser = Parser {
+            tokens: &substituted,
+            pos: 0,
+            diagnostics: Vec::new(),
+            inherited_variables: self.inherited_variables,
+            depth: self.depth,
+        };
+        sub_parser.parse_statements_into(node, arena, macro_stack);
+        self.diagnostics.extend(sub_parser.diagnostics);
+        macro_stack.pop();
+    }
+
+    /// Reads a single literal value, along with a `Transparency` fraction if it was a color
+    /// literal that carried an alpha channel.
+    fn read_value(&mut self) -> Option<(DataType<'a>, Option<f64>)> {
+        match self.peek().cloned() {
+            Some(Token::DataType(DataType::ColorHex(hex))) => {
+                self.bump();
+                let (color, transparency) = parse_hex_color(hex)?;
+                Some((DataType::Color3(color), transparency))
+            }
+            Some(Token::ColorFunction(name)) => {
+                self.bump();
+                let (color, transparency) = self.parse_color_function(name)?;
+                Some((DataType::Color3(color), transparency))
+            }
+            Some(Token::DataType(value)) => {
+                self.bump();
+                Some((value, None))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reads a single expression operand: a `$variable` reference resolved against `node`'s
+    /// already-parsed variables, or a literal value via [`Self::read_value`].
+    fn read_primary(&mut self, node: &TokenTreeNode<'a>) -> Option<(DataType<'a>, Option<f64>)> {
+        match self.peek().cloned() {
+            Some(Token::Text(TextType::Variable(name))) => {
+                self.bump();
+                if let Some(value) = node.variables.get(name) {
+                    return Some((value.clone(), None));
+                }
+                if let Some(value) = self.inherited_variables.get(name) {
+                    return Some((value.clone(), None));
+                }
+                let span = self.span_at(self.pos - 1);
+                self.error(span, format!("undefined variable '${name}'"));
+                None
+            }
+            _ => self.read_value(),
+        }
+    }
+
+    /// Reads a full arithmetic expression over [`Operator`] tokens and `(`/`)` grouping, folding
+    /// it down to a single [`DataType`] with a shunting-yard pass into RPN followed by
+    /// [`evaluate_rpn`]. A bare literal or variable reference is just a one-token expression.
+    fn read_expr(&mut self, node: &TokenTreeNode<'a>) -> Option<(DataType<'a>, Option<f64>)> {
+        let mut output: Vec<ExprToken<'a>> = Vec::new();
+        let mut op_stack: Vec<StackEntry> = Vec::new();
+        let mut transparency = None;
+        let mut expect_value = true;
+
+        loop {
+            if expect_value {
+                if matches!(self.peek(), Some(Token::TupleOpen)) {
+                    self.bump();
+                    op_stack.push(StackEntry::LParen);
+                    continue;
+                }
+                let (value, value_transparency) = self.read_primary(node)?;
+                if value_transparency.is_some() {
+                    transparency = value_transparency;
+                }
+                output.push(ExprToken::Value(value));
+                expect_value = false;
+            } else {
+                match self.peek().cloned() {
+                    Some(Token::Operator(op)) => {
+                        self.bump();
+                        loop {
+                            let should_pop = match op_stack.last() {
+                                Some(StackEntry::Op(top)) if op == Operator::Pow => precedence(top) > precedence(&op),
+                                Some(StackEntry::Op(top)) => precedence(top) >= precedence(&op),
+                                _ => false,
+                            };
+                            if !should_pop {
+                                break;
+                            }
+                            if let Some(StackEntry::Op(top)) = op_stack.pop() {
+                                output.push(ExprToken::Op(top));
+                            }
+                        }
+                        op_stack.push(StackEntry::Op(op));
+                        expect_value = true;
+                    }
+                    Some(Token::TupleClose) => {
+                        self.bump();
+                        while let Some(entry) = op_stack.pop() {
+                            match entry {
+                                StackEntry::Op(op) => output.push(ExprToken::Op(op)),
+                                StackEntry::LParen => break,
+                            }
+                        }
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        while let Some(entry) = op_stack.pop() {
+            if let StackEntry::Op(op) = entry {
+                output.push(ExprToken::Op(op));
+            }
+        }
+
+        match evaluate_rpn(&output) {
+            Ok(value) => Some((value, transparency)),
+            Err(message) => {
+                let span = self.span_at(self.pos);
+                self.error(span, message);
+                None
+            }
+        }
+    }
+
+    /// Parses `name(arg, arg, ...)` for `rgb`/`rgba`/`hsl`/`hsla`, assuming `name` has already
+    /// been consumed.
+    fn parse_color_function(&mut self, name: &'a str) -> Option<(Color3, Option<f64>)> {
+        if !matches!(self.peek(), Some(Token::TupleOpen)) {
+            return None;
+        }
+        self.bump();
+
+        let mut args = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::DataType(DataType::Number(n))) => args.push(*n),
+                Some(Token::DataType(DataType::NumberScale(n))) => args.push(*n),
+                _ => break,
+            }
+            self.bump();
+            if matches!(self.peek(), Some(Token::ListDelimiter)) {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+
+        if matches!(self.peek(), Some(Token::TupleClose)) {
+            self.bump();
+        }
+
+        if args.len() < 3 {
+            return None;
+        }
+
+        let (r, g, b) = match name {
+            "rgb" | "rgba" => (args[0] as f32 / 255.0, args[1] as f32 / 255.0, args[2] as f32 / 255.0),
+            "hsl" | "hsla" => hsl_to_rgb(args[0], args[1], args[2]),
+            _ => return None,
+        };
+
+        let transparency = args.get(3).map(|alpha| 1.0 - alpha);
+        Some((Color3::new(r, g, b), transparency))
+    }
+
+    fn parse_selector(&mut self) -> Option<String> {
+        let mut selector = String::new();
+        let mut saw_any = false;
+
+        loop {
+            match self.peek() {
+                Some(Token::Text(text_type)) => {
+                    selector.push_str(&selector_fragment(text_type));
+                    saw_any = true;
+                    self.bump();
+                }
+                Some(Token::EnumKeyword) => {
+                    selector.push_str("Enum");
+                    saw_any = true;
+                    self.bump();
+                }
+                Some(Token::Colon) => {
+                    selector.push(':');
+                    self.bump();
+                }
+                Some(Token::ScopeToChildren) => {
+                    selector.push_str(" > ");
+                    self.bump();
+                }
+                Some(Token::ScopeToDescendants) => {
+                    selector.push_str(" >> ");
+                    self.bump();
+                }
+                Some(Token::ListDelimiter) => {
+                    selector.push_str(", ");
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+
+        saw_any.then_some(selector)
+    }
+}
+
+/// Parses a token stream (without diagnostics) into a tree of [`TokenTreeNode`]s, rooted at
+/// index `0`. Kept for callers that only have a plain [`Token`] slice; it recovers from
+/// malformed input the same way [`parse_rsml_diagnostics`] does, but discards the diagnostics,
+/// and every token carries an empty `0..0` span.
+pub fn parse_rsml<'a>(tokens: &[Token<'a>]) -> Arena<'a, TokenTreeNode<'a>> {
+    let spanned: Vec<SpannedToken<'a>> = tokens.iter().cloned().map(|token| (token, 0..0)).collect();
+    parse_rsml_diagnostics(&spanned).0
+}
+
+/// Parses a spanned token stream into a tree of [`TokenTreeNode`]s, recovering from malformed
+/// statements by resyncing at the next `;` or `}` instead of aborting the whole file.
+pub fn parse_rsml_diagnostics<'a>(
+    tokens: &[SpannedToken<'a>],
+) -> (Arena<'a, TokenTreeNode<'a>>, Vec<LexDiagnostic>) {
+    parse_rsml_diagnostics_with_inherited(tokens, &HashMap::new())
+}
+
+/// Parses a spanned token stream like [`parse_rsml_diagnostics`], but seeds `$variable` lookups
+/// with `inherited_variables` first, falling back to it whenever a name isn't defined on the
+/// node doing the lookup. This is how `@derive` ancestors' variables become visible to a
+/// dependent stylesheet: the caller resolves the ancestor chain and passes the merged result in.
+pub fn parse_rsml_diagnostics_with_inherited<'a>(
+    tokens: &[SpannedToken<'a>],
+    inherited_variables: &HashMap<String, DataType<'static>>,
+) -> (Arena<'a, TokenTreeNode<'a>>, Vec<LexDiagnostic>) {
+    let mut arena = Arena::new();
+    let mut parser = Parser { tokens, pos: 0, diagnostics: Vec::new(), inherited_variables, depth: 0 };
+    let mut macro_stack = Vec::new();
+
+    let root = parser.parse_body(&mut arena, &mut macro_stack);
+    let root_idx = arena.alloc(root);
+    debug_assert_eq!(root_idx, 0, "root node must be allocated at index 0");
+
+    (arena, parser.diagnostics)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hex_color_three_digit_expands_nibbles_by_duplication() {
+        let (color, transparency) = parse_hex_color("abc").unwrap();
+        assert_eq!(color, Color3::new(0xAA as f32 / 255.0, 0xBB as f32 / 255.0, 0xCC as f32 / 255.0));
+        assert_eq!(transparency, None);
+    }
+
+    #[test]
+    fn hex_color_six_digit_has_no_alpha() {
+        let (color, transparency) = parse_hex_color("336699").unwrap();
+        assert_eq!(color, Color3::new(0x33 as f32 / 255.0, 0x66 as f32 / 255.0, 0x99 as f32 / 255.0));
+        assert_eq!(transparency, None);
+    }
+
+    #[test]
+    fn hex_color_eight_digit_decodes_alpha_to_transparency() {
+        let (color, transparency) = parse_hex_color("336699cc").unwrap();
+        assert_eq!(color, Color3::new(0x33 as f32 / 255.0, 0x66 as f32 / 255.0, 0x99 as f32 / 255.0));
+        assert_eq!(transparency, Some(1.0 - 0xcc as f64 / 255.0));
+    }
+
+    #[test]
+    fn hex_color_rejects_other_lengths() {
+        assert_eq!(parse_hex_color("ab"), None);
+        assert_eq!(parse_hex_color("abcde"), None);
+    }
+
+    #[test]
+    fn hsl_to_rgb_primary_hues() {
+        assert_eq!(hsl_to_rgb(0.0, 1.0, 0.5), (1.0, 0.0, 0.0));
+        assert_eq!(hsl_to_rgb(120.0, 1.0, 0.5), (0.0, 1.0, 0.0));
+        assert_eq!(hsl_to_rgb(240.0, 1.0, 0.5), (0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn hsl_to_rgb_zero_saturation_is_a_gray() {
+        assert_eq!(hsl_to_rgb(0.0, 0.0, 0.5), (0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn functional_rgb_and_hsla_colors_parse_to_color3() {
+        let (tokens, lex_diagnostics) = lex_rsml_diagnostics(
+            "TextButton { Color = rgb(51, 102, 153); Accent = hsla(0, 100%, 50%, 0.5); }",
+        );
+        assert!(lex_diagnostics.is_empty());
+
+        let (arena, parse_diagnostics) = parse_rsml_diagnostics(&tokens);
+        assert!(parse_diagnostics.is_empty());
+
+        let root = arena.get(0).unwrap();
+        let rule_idx = root.rules.0.get("TextButton").unwrap()[0];
+        let rule = arena.get(rule_idx).unwrap();
+
+        assert_eq!(
+            rule.properties.get("Color"),
+            Some(&DataType::Color3(Color3::new(51.0 / 255.0, 102.0 / 255.0, 153.0 / 255.0)))
+        );
+        assert_eq!(rule.properties.get("Accent"), Some(&DataType::Color3(Color3::new(1.0, 0.0, 0.0))));
+        assert_eq!(rule.transparency, Some(0.5));
+    }
+
+    #[test]
+    fn derive_below_the_root_is_rejected() {
+        let (tokens, lex_diagnostics) = lex_rsml_diagnostics(
+            "TextButton { @derive 'base.rsml'; Size = 10px; }",
+        );
+        assert!(lex_diagnostics.is_empty());
+
+        let (arena, parse_diagnostics) = parse_rsml_diagnostics(&tokens);
+        assert!(parse_diagnostics.iter().any(|diagnostic| diagnostic.message.contains("only allowed at the top level")));
+
+        // The rest of the rule body should still parse despite the nested `@derive`.
+        let root = arena.get(0).unwrap();
+        let rule_idx = root.rules.0.get("TextButton").unwrap()[0];
+        let rule = arena.get(rule_idx).unwrap();
+        assert_eq!(rule.properties.get("Size"), Some(&DataType::NumberOffset(10.0)));
+    }
+}
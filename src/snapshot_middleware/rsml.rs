@@ -1,5 +1,9 @@
 // Modules -------------------------------------------------------------------------------------------
-use std::{collections::HashMap, path::Path, str::FromStr};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use sha2::{Sha256, Digest};
 use normalize_path::NormalizePath;
 
@@ -11,22 +15,127 @@ use super::meta_file::AdjacentMetadata;
 
 use rbx_dom_weak::types::{Attributes, Ref, Variant};
 
-use rbx_rsml::{lex_rsml, parse_rsml, Arena, TokenTreeNode};
+use rbx_rsml::{
+    data_type_to_variant, lex_rsml_diagnostics, parse_rsml_diagnostics,
+    parse_rsml_diagnostics_with_inherited, Arena, DataType, SpannedToken, Token, TokenTreeNode,
+};
 // ---------------------------------------------------------------------------------------------------
 
 
 // Functions -----------------------------------------------------------------------------------------
-fn attributes_from_hashmap(variables: &HashMap<&str, Variant>) -> Attributes {
+fn attributes_from_hashmap(variables: &HashMap<&str, DataType>) -> Attributes {
     let mut attributes = Attributes::new();
     if !variables.is_empty() {
         for (key, value) in variables {
-            attributes.insert(key.to_string(), value.clone());
+            attributes.insert(key.to_string(), data_type_to_variant(value));
         }
     }
 
     attributes
 }
 
+/// Strips a [`DataType`]'s borrow on its source text so it can outlive the file it was parsed
+/// from, for carrying a `@derive` ancestor's resolved variables across into a dependent file's
+/// arena. The borrowed string variants fold to [`DataType::OwnedString`]; every other variant is
+/// already `'static`-safe. [`data_type_to_variant`] treats all of the string-ish variants
+/// identically, so this loses no information that ever reaches the final snapshot.
+fn own_data_type(value: &DataType) -> DataType<'static> {
+    match value {
+        DataType::ColorHex(s) | DataType::ColorTw(s) | DataType::ColorCss(s)
+        | DataType::ColorBc(s) | DataType::StringSingle(s) => DataType::OwnedString((*s).to_string()),
+        DataType::OwnedString(s) => DataType::OwnedString(s.clone()),
+        DataType::NumberOffset(n) => DataType::NumberOffset(*n),
+        DataType::NumberScale(n) => DataType::NumberScale(*n),
+        DataType::Number(n) => DataType::Number(*n),
+        DataType::Bool(b) => DataType::Bool(*b),
+        DataType::Tuple(n) => DataType::Tuple(*n),
+        DataType::UDim(v) => DataType::UDim(*v),
+        DataType::UDim2(v) => DataType::UDim2(*v),
+        DataType::Vec2(v) => DataType::Vec2(*v),
+        DataType::Rect(v) => DataType::Rect(*v),
+        DataType::Vec3(v) => DataType::Vec3(*v),
+        DataType::Color3(v) => DataType::Color3(*v),
+        DataType::Font(v) => DataType::Font(v.clone()),
+    }
+}
+
+/// Scans a token stream for top-level `@derive 'path';` statements without building a full
+/// token tree, so derive targets can be resolved before the owning file is parsed (its own
+/// parse needs the ancestors' variables already in hand). Nested rule bodies are skipped, since
+/// only the root of a stylesheet may derive from another.
+fn scan_derive_paths<'a>(tokens: &[SpannedToken<'a>]) -> Vec<&'a str> {
+    let mut paths = Vec::new();
+    let mut depth = 0;
+
+    for (index, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            Token::ScopeOpen => depth += 1,
+            Token::ScopeClose => depth -= 1,
+            Token::DeriveDeclaration if depth == 0 => {
+                if let Some((Token::DataType(DataType::StringSingle(path)), _)) = tokens.get(index + 1) {
+                    paths.push(*path);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    paths
+}
+
+/// Resolves a `@derive` target against the directory of the file that declared it: `./`-relative
+/// and bare module-name targets are both joined onto that directory, matching how module
+/// resolution works in other language front-ends.
+fn resolve_derive_path(base_file: &Path, derive: &str) -> PathBuf {
+    let base_dir = base_file.parent().unwrap_or_else(|| Path::new(""));
+    let relative = derive.strip_prefix("./").unwrap_or(derive);
+
+    base_dir.join(relative).normalize()
+}
+
+/// Reads and parses a `@derive` ancestor, recursively resolving its own ancestors first, and
+/// returns the fully merged `$variable` lookup a descendant should inherit: parent entries with
+/// entries from `path` itself layered on top, so the nearer file always shadows the further one.
+///
+/// `chain` is the sequence of files currently being resolved, used to detect and report a
+/// circular `@derive` chain; `relevant_paths` accumulates every ancestor file touched so the
+/// caller can make Rojo re-sync dependents when any of them change.
+fn resolve_ancestor_variables(
+    vfs: &Vfs,
+    path: &Path,
+    chain: &mut Vec<PathBuf>,
+    relevant_paths: &mut Vec<PathBuf>,
+) -> anyhow::Result<HashMap<String, DataType<'static>>> {
+    if let Some(cycle_start) = chain.iter().position(|visited| visited == path) {
+        let mut cycle: Vec<String> = chain[cycle_start..].iter().map(|p| p.display().to_string()).collect();
+        cycle.push(path.display().to_string());
+        anyhow::bail!("circular @derive chain: {}", cycle.join(" -> "));
+    }
+
+    let contents = vfs.read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("could not read derived stylesheet '{}': {err}", path.display()))?;
+    let (tokens, _lex_diagnostics) = lex_rsml_diagnostics(&contents);
+
+    chain.push(path.to_path_buf());
+
+    let mut inherited = HashMap::new();
+    for derive in scan_derive_paths(&tokens) {
+        let derive_path = resolve_derive_path(path, derive);
+        relevant_paths.push(derive_path.clone());
+        inherited.extend(resolve_ancestor_variables(vfs, &derive_path, chain, relevant_paths)?);
+    }
+
+    let (arena, _parse_diagnostics) = parse_rsml_diagnostics_with_inherited(&tokens, &inherited);
+    let root_node = arena.get(0).unwrap();
+    for (name, value) in &root_node.variables {
+        inherited.insert((*name).to_string(), own_data_type(value));
+    }
+
+    chain.pop();
+
+    Ok(inherited)
+}
+
 fn apply_token_tree_to_stylesheet_snapshot(
     mut snapshot: InstanceSnapshot, selector: &str, data: &TokenTreeNode, arena: &Arena<TokenTreeNode>
 ) -> InstanceSnapshot {
@@ -48,6 +157,7 @@ fn apply_token_tree_to_stylesheet_snapshot(
     let mut properties: HashMap<String, Variant> = HashMap::new();
     properties.insert("Selector".into(), Variant::String(selector.to_string()));
     if let Some(priority) = data.priority { properties.insert("Priority".into(), Variant::Int32(priority)); }
+    if let Some(transparency) = data.transparency { properties.insert("Transparency".into(), Variant::Float32(transparency as f32)); }
     if !attributes.is_empty() { properties.insert("Attributes".into(), attributes.into()); }
     if !styled_properties.is_empty() { properties.insert("StyledProperties".into(), styled_properties.into()); }
 
@@ -74,23 +184,37 @@ pub fn snapshot_rsml<'a>(
     let contents = vfs.read_to_string(path)?;
     let contents_str = contents.as_str();
 
-    let tokens = lex_rsml(contents_str);
-    let token_tree_arena = parse_rsml(&tokens);
+    let (tokens, lex_diagnostics) = lex_rsml_diagnostics(contents_str);
 
     let meta_path = path.with_file_name(format!("{}.meta.json", name));
+    let normalized_path = path.normalize();
+
+    let derives: Vec<PathBuf> = scan_derive_paths(&tokens)
+        .into_iter()
+        .map(|derive| resolve_derive_path(path, derive))
+        .collect();
+
+    let mut chain = vec![normalized_path.clone()];
+    let mut relevant_paths = vec![path.to_path_buf(), meta_path.clone()];
+    let mut inherited_variables = HashMap::new();
+    for derive_path in &derives {
+        relevant_paths.push(derive_path.clone());
+        inherited_variables.extend(resolve_ancestor_variables(vfs, derive_path, &mut chain, &mut relevant_paths)?);
+    }
 
-    let root_node = &token_tree_arena.get(0).unwrap();
+    let (token_tree_arena, parse_diagnostics) = parse_rsml_diagnostics_with_inherited(&tokens, &inherited_variables);
 
-    let derives = &root_node.derives.iter()
-        .map(|x| {
-            match x.starts_with("./") {
-                true => path.join("..").join(Path::new(x)).normalize().to_str().unwrap().to_string(),
-                false => Path::new(x).normalize().to_str().unwrap().to_string()
-            }
-        })
-        .collect::<Vec<String>>();
+    // The lexer/parser resync past most mistakes and keep producing a usable tree, so a
+    // single diagnostic shouldn't take down the whole `.rsml` file's sync. Report every
+    // diagnostic so the problem is visible, but still build the snapshot from the
+    // (possibly degraded) recovered tree rather than discarding it.
+    for diagnostic in lex_diagnostics.iter().chain(parse_diagnostics.iter()) {
+        log::warn!("{}:{}: {}", path.display(), diagnostic.span.start, diagnostic.message);
+    }
+
+    let root_node = &token_tree_arena.get(0).unwrap();
 
-    let path_as_ref_string = path_to_ref_string(path.normalize().to_str().unwrap());
+    let path_as_ref_string = path_to_ref_string(normalized_path.to_str().unwrap());
 
     let mut snapshot = InstanceSnapshot::new()
         .name(name)
@@ -99,7 +223,7 @@ pub fn snapshot_rsml<'a>(
         .metadata(
             InstanceMetadata::new()
                 .instigating_source(path)
-                .relevant_paths([path.to_path_buf(), meta_path.clone()].into())
+                .relevant_paths(relevant_paths.into_iter().collect())
                 .context(context)
         );
 
@@ -128,13 +252,10 @@ pub fn snapshot_rsml<'a>(
         }
     }
 
-    for path in derives {
-        let name = match Path::new(path).file_stem() {
-            Some(file_stem) => match file_stem.to_str() {
-                Some(file) => &format!("{} (Derive)", file),
-                None => "StyleDerive"
-            },
-            None => "StyleDerive"
+    for derive_path in &derives {
+        let name = match derive_path.file_stem().and_then(|file_stem| file_stem.to_str()) {
+            Some(file) => format!("{} (Derive)", file),
+            None => "StyleDerive".to_string()
         };
 
         snapshot.children.push(
@@ -142,7 +263,7 @@ pub fn snapshot_rsml<'a>(
                 .name(name)
                 .class_name("StyleDerive")
                 .properties([
-                    ("StyleSheet".into(), Variant::Ref(Ref::from_str(&path_to_ref_string(path)).unwrap()))
+                    ("StyleSheet".into(), Variant::Ref(Ref::from_str(&path_to_ref_string(derive_path.to_str().unwrap())).unwrap()))
                 ])
         );
     }
@@ -176,4 +297,151 @@ mod test {
 
         insta::assert_yaml_snapshot!(instance_snapshot);
     }
+
+    #[test]
+    fn expression_folds_same_and_cross_unit_arithmetic() {
+        let mut imfs = InMemoryFs::new();
+        imfs.load_snapshot(
+            "/foo.rsml",
+            VfsSnapshot::file(
+                "TextButton { Size = 50% + 10px; Padding = 10px + 5px; Weight = 50% + 25%; }",
+            ),
+        )
+        .unwrap();
+
+        let mut vfs = Vfs::new(imfs.clone());
+
+        let instance_snapshot = snapshot_rsml(
+            &InstanceContext::default(),
+            &mut vfs,
+            Path::new("/foo.rsml"),
+            "foo",
+        )
+        .unwrap()
+        .unwrap();
+
+        let styled_properties = match instance_snapshot.children[0].properties.get("StyledProperties") {
+            Some(Variant::Attributes(attributes)) => attributes,
+            other => panic!("expected StyledProperties attributes, got {other:?}"),
+        };
+
+        assert_eq!(styled_properties.get("Size"), Some(&Variant::UDim(rbx_dom_weak::types::UDim::new(0.5, 10))));
+        assert_eq!(styled_properties.get("Padding"), Some(&Variant::Float64(15.0)));
+        assert_eq!(styled_properties.get("Weight"), Some(&Variant::Float64(0.75)));
+    }
+
+    #[test]
+    fn macro_invocation_splices_expanded_body() {
+        let mut imfs = InMemoryFs::new();
+        imfs.load_snapshot(
+            "/foo.rsml",
+            VfsSnapshot::file(
+                "@macro tinted_button(color) { BackgroundColor3 = $!color; }\n\
+                 TextButton { @tinted_button(#336699); }",
+            ),
+        )
+        .unwrap();
+
+        let mut vfs = Vfs::new(imfs.clone());
+
+        let instance_snapshot = snapshot_rsml(
+            &InstanceContext::default(),
+            &mut vfs,
+            Path::new("/foo.rsml"),
+            "foo",
+        )
+        .unwrap()
+        .unwrap();
+
+        let styled_properties = match instance_snapshot.children[0].properties.get("StyledProperties") {
+            Some(Variant::Attributes(attributes)) => attributes,
+            other => panic!("expected StyledProperties attributes, got {other:?}"),
+        };
+
+        let expected = rbx_dom_weak::types::Color3::new(0x33 as f32 / 255.0, 0x66 as f32 / 255.0, 0x99 as f32 / 255.0);
+        assert_eq!(styled_properties.get("BackgroundColor3"), Some(&Variant::Color3(expected)));
+    }
+
+    #[test]
+    fn derive_inherits_ancestor_variables() {
+        let mut imfs = InMemoryFs::new();
+        imfs.load_snapshot("/base.rsml", VfsSnapshot::file("$brand = #336699;"))
+            .unwrap();
+        imfs.load_snapshot(
+            "/foo.rsml",
+            VfsSnapshot::file("@derive 'base.rsml'; $accent = $brand;"),
+        )
+        .unwrap();
+
+        let mut vfs = Vfs::new(imfs.clone());
+
+        let instance_snapshot = snapshot_rsml(
+            &InstanceContext::default(),
+            &mut vfs,
+            Path::new("/foo.rsml"),
+            "foo",
+        )
+        .unwrap()
+        .unwrap();
+
+        let attributes = match instance_snapshot.properties.get("Attributes") {
+            Some(Variant::Attributes(attributes)) => attributes,
+            other => panic!("expected Attributes, got {other:?}"),
+        };
+
+        let expected = rbx_dom_weak::types::Color3::new(0x33 as f32 / 255.0, 0x66 as f32 / 255.0, 0x99 as f32 / 255.0);
+        assert_eq!(attributes.get("accent"), Some(&Variant::Color3(expected)));
+    }
+
+    #[test]
+    fn derive_cycle_is_rejected() {
+        let mut imfs = InMemoryFs::new();
+        imfs.load_snapshot("/a.rsml", VfsSnapshot::file("@derive 'b.rsml';"))
+            .unwrap();
+        imfs.load_snapshot("/b.rsml", VfsSnapshot::file("@derive 'a.rsml';"))
+            .unwrap();
+
+        let mut vfs = Vfs::new(imfs.clone());
+
+        let error = snapshot_rsml(
+            &InstanceContext::default(),
+            &mut vfs,
+            Path::new("/a.rsml"),
+            "a",
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("circular @derive chain"));
+    }
+
+    #[test]
+    fn malformed_statement_does_not_discard_the_rest_of_the_file() {
+        let mut imfs = InMemoryFs::new();
+        imfs.load_snapshot(
+            "/foo.rsml",
+            VfsSnapshot::file("; TextButton { Size = 10px; }"),
+        )
+        .unwrap();
+
+        let mut vfs = Vfs::new(imfs.clone());
+
+        // The stray `;` at the top of the file is an unexpected token with no valid
+        // statement to attach to, but the parser should resync past it instead of
+        // discarding the rest of the (still-valid) file.
+        let instance_snapshot = snapshot_rsml(
+            &InstanceContext::default(),
+            &mut vfs,
+            Path::new("/foo.rsml"),
+            "foo",
+        )
+        .unwrap()
+        .unwrap();
+
+        let styled_properties = match instance_snapshot.children[0].properties.get("StyledProperties") {
+            Some(Variant::Attributes(attributes)) => attributes,
+            other => panic!("expected StyledProperties attributes, got {other:?}"),
+        };
+
+        assert_eq!(styled_properties.get("Size"), Some(&Variant::UDim(rbx_dom_weak::types::UDim::new(0.0, 10))));
+    }
 }
\ No newline at end of file